@@ -0,0 +1,260 @@
+use std::fmt::{Display, Formatter};
+
+/// Minimal arbitrary precision unsigned integer, just enough to compute program numbers.
+/// Stored as little endian base 2^32 limbs, with no trailing zero limbs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BigUint {
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    pub fn zero() -> Self {
+        BigUint { limbs: Vec::new() }
+    }
+
+    pub fn one() -> Self {
+        BigUint::from(1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    /// Number of significant bits
+    pub fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(last) => self.limbs.len() * 32 - last.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    pub fn to_usize(&self) -> Option<usize> {
+        if self.bits() > usize::BITS as usize {
+            return None;
+        }
+        Some(self.limbs.iter().rev().fold(0, |acc, &limb| (acc << 32) | limb as usize))
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        if self.is_zero() || other.is_zero() {
+            return BigUint::zero();
+        }
+
+        let mut limbs = vec![0u32; self.limbs.len() + other.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let t = a as u64 * b as u64 + limbs[i + j] as u64 + carry;
+                limbs[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            limbs[i + other.limbs.len()] = carry as u32;
+        }
+
+        BigUint { limbs }.normalized()
+    }
+
+    pub fn pow(&self, mut exp: usize) -> Self {
+        let mut base = self.clone();
+        let mut result = BigUint::one();
+        while exp > 0 {
+            if exp & 1 == 1 { result = result.mul(&base); }
+            exp >>= 1;
+            if exp > 0 { base = base.mul(&base); }
+        }
+        result
+    }
+
+    pub fn shl(&self, bits: usize) -> Self {
+        if self.is_zero() {
+            return BigUint::zero();
+        }
+
+        let (offset, shift) = (bits / 32, bits % 32);
+        let mut limbs = vec![0u32; offset];
+        let mut carry = 0u32;
+        for &limb in &self.limbs {
+            limbs.push((limb << shift) | carry);
+            carry = if shift == 0 { 0 } else { limb >> (32 - shift) };
+        }
+        limbs.push(carry);
+
+        BigUint { limbs }.normalized()
+    }
+
+    /// Parse a number from a string of decimal digits
+    pub fn parse(digits: &str) -> Option<Self> {
+        if digits.is_empty() {
+            return None;
+        }
+
+        let ten = BigUint::from(10);
+        digits.chars().try_fold(BigUint::zero(), |n, c| Some(n.mul(&ten).add_small(c.to_digit(10)?)))
+    }
+
+    pub fn add_one(&self) -> Self {
+        self.add_small(1)
+    }
+
+    fn add_small(&self, n: u32) -> Self {
+        let mut limbs = self.limbs.clone();
+        let mut carry = n;
+        for limb in &mut limbs {
+            let (sum, overflow) = limb.overflowing_add(carry);
+            *limb = sum;
+            carry = overflow as u32;
+            if carry == 0 { return BigUint { limbs }; }
+        }
+        if carry > 0 { limbs.push(carry); }
+        BigUint { limbs }
+    }
+
+    /// Subtract one, saturating at zero
+    pub fn sub_one(&self) -> Self {
+        let mut limbs = self.limbs.clone();
+        for limb in &mut limbs {
+            let (diff, overflow) = limb.overflowing_sub(1);
+            *limb = diff;
+            if !overflow { break; }
+        }
+        BigUint { limbs }.normalized()
+    }
+
+    /// Divide by a small number, returning the quotient and remainder
+    pub fn div_rem(&self, divisor: u32) -> (Self, u32) {
+        let mut limbs = self.limbs.clone();
+        let mut rem = 0u64;
+        for limb in limbs.iter_mut().rev() {
+            let t = (rem << 32) | *limb as u64;
+            *limb = (t / divisor as u64) as u32;
+            rem = t % divisor as u64;
+        }
+        (BigUint { limbs }.normalized(), rem as u32)
+    }
+
+    fn normalized(mut self) -> Self {
+        while self.limbs.last() == Some(&0) { self.limbs.pop(); }
+        self
+    }
+}
+
+impl From<usize> for BigUint {
+    fn from(mut n: usize) -> Self {
+        let mut limbs = Vec::new();
+        while n > 0 {
+            limbs.push(n as u32);
+            n = n.checked_shr(32).unwrap_or(0);
+        }
+        BigUint { limbs }
+    }
+}
+
+impl Display for BigUint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // Split into base 10^9 chunks, least significant first
+        let mut chunks = Vec::new();
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let (q, r) = n.div_rem(1_000_000_000);
+            chunks.push(r);
+            n = q;
+        }
+
+        write!(f, "{}", chunks.last().unwrap())?;
+        for chunk in chunks.iter().rev().skip(1) {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(digits: &str) -> BigUint {
+        BigUint::parse(digits).unwrap()
+    }
+
+    #[test]
+    fn mul_carries_across_limbs() {
+        let max_limb = BigUint::from(u32::MAX as usize);
+        assert_eq!(max_limb.mul(&max_limb), big("18446744065119617025"));
+        assert_eq!(big("18446744073709551616").mul(&big("18446744073709551616")),
+                   big("340282366920938463463374607431768211456"));
+        assert_eq!(max_limb.mul(&BigUint::zero()), BigUint::zero());
+    }
+
+    #[test]
+    fn pow() {
+        assert_eq!(BigUint::from(2).pow(128), big("340282366920938463463374607431768211456"));
+        assert_eq!(BigUint::from(3).pow(46), big("8862938119652501095929"));
+        assert_eq!(BigUint::from(7).pow(0), BigUint::one());
+    }
+
+    #[test]
+    fn shl_at_limb_boundaries() {
+        let one = BigUint::one();
+        assert_eq!(one.shl(0), one);
+        assert_eq!(one.shl(31), BigUint::from(1 << 31));
+        assert_eq!(one.shl(32), BigUint::from(1 << 32));
+        assert_eq!(one.shl(33), BigUint::from(1 << 33));
+        assert_eq!(BigUint::from(u32::MAX as usize).shl(1), BigUint::from((u32::MAX as usize) << 1));
+        assert_eq!(one.shl(64), big("18446744073709551616"));
+        assert_eq!(BigUint::zero().shl(40), BigUint::zero());
+    }
+
+    #[test]
+    fn add_and_sub_one_borrow_across_limbs() {
+        let two_pow_64 = big("18446744073709551616");
+        assert_eq!(two_pow_64.sub_one(), BigUint::from(usize::MAX));
+        assert_eq!(BigUint::from(usize::MAX).add_one(), two_pow_64);
+        assert_eq!(BigUint::one().sub_one(), BigUint::zero());
+        assert_eq!(BigUint::zero().sub_one(), BigUint::zero());
+    }
+
+    #[test]
+    fn div_rem() {
+        assert_eq!(big("18446744073709551616").div_rem(10), (big("1844674407370955161"), 6));
+        assert_eq!(BigUint::from(1_000_000_000).div_rem(1_000_000_000), (BigUint::one(), 0));
+        assert_eq!(BigUint::from(7).div_rem(9), (BigUint::zero(), 7));
+    }
+
+    #[test]
+    fn to_usize() {
+        assert_eq!(BigUint::from(usize::MAX).to_usize(), Some(usize::MAX));
+        assert_eq!(big("18446744073709551616").to_usize(), None);
+        assert_eq!(BigUint::zero().to_usize(), Some(0));
+    }
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        for digits in [
+            "0",
+            "1",
+            "999999999",
+            "1000000000",
+            "1000000001",
+            "4294967295",
+            "4294967296",
+            "1000000000000000000",
+            "1000000000000000001",
+            "18446744073709551616",
+            "340282366920938463463374607431768211456",
+        ] {
+            assert_eq!(big(digits).to_string(), digits);
+        }
+        assert_eq!(big("0007").to_string(), "7");
+    }
+
+    #[test]
+    fn parse_rejects_non_digits() {
+        assert_eq!(BigUint::parse(""), None);
+        assert_eq!(BigUint::parse("12x"), None);
+        assert_eq!(BigUint::parse("-1"), None);
+    }
+}
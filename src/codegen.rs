@@ -0,0 +1,224 @@
+use crate::error::CodegenError;
+use crate::program::{Instruction, Program, Variable};
+
+// =================================================================================================
+// Tape layout
+// =================================================================================================
+//
+// | 0   | 1      | 2      | 3 ... 3 + n            | 4 + n ...                        |
+// | run | temp a | temp b | instruction flags, halt | variables y, x1, z1, x2, z2, ... |
+//
+// Execution is a loop over every instruction while the run cell is set. Exactly one
+// instruction flag is set at a time, acting as the program counter: each instruction runs only
+// if its flag is set, and sets the flag of the next instruction to run. The final flag halts the
+// machine by clearing the run cell.
+
+const RUN: usize = 0;
+const TEMP_A: usize = 1;
+const TEMP_B: usize = 2;
+const FLAGS: usize = 3;
+
+struct Codegen {
+    code: String,
+    ptr: usize,
+}
+
+impl Codegen {
+    fn goto(&mut self, cell: usize) {
+        let c = if cell > self.ptr { '>' } else { '<' };
+        for _ in 0..cell.abs_diff(self.ptr) { self.code.push(c); }
+        self.ptr = cell;
+    }
+
+    fn emit(&mut self, cell: usize, c: char) {
+        self.goto(cell);
+        self.code.push(c);
+    }
+
+    fn inc(&mut self, cell: usize) { self.emit(cell, '+'); }
+
+    fn dec(&mut self, cell: usize) { self.emit(cell, '-'); }
+
+    fn open(&mut self, cell: usize) { self.emit(cell, '['); }
+
+    fn close(&mut self, cell: usize) { self.emit(cell, ']'); }
+
+    fn clear(&mut self, cell: usize) {
+        self.open(cell);
+        self.dec(cell);
+        self.close(cell);
+    }
+
+    /// Add the value of src to dst, preserving src. Uses temp b.
+    fn copy(&mut self, src: usize, dst: usize) {
+        self.open(src);
+        self.dec(src);
+        self.inc(dst);
+        self.inc(TEMP_B);
+        self.close(src);
+
+        self.open(TEMP_B);
+        self.dec(TEMP_B);
+        self.inc(src);
+        self.close(TEMP_B);
+    }
+}
+
+impl Program {
+    /// Compile the program to brainfuck. Inputs are read from stdin as one byte per input
+    /// variable, and the value of y is written to stdout as a single byte on halt.
+    pub fn to_brainfuck(&self) -> Result<String, CodegenError> {
+        let halt = FLAGS + self.instructions.len();
+        let var_cell = |var: &Variable| halt + 1 + var.get_number();
+        let mut bf = Codegen { code: String::new(), ptr: 0 };
+
+        // Read inputs
+        let inputs = self.instructions.iter().filter_map(|instruction| match instruction {
+            Instruction::Increment { var: Variable::X(n) }
+            | Instruction::Decrement { var: Variable::X(n) }
+            | Instruction::JumpNonZero { var: Variable::X(n), .. } => Some(*n),
+            _ => None,
+        }).max().unwrap_or(0);
+
+        for n in 1..=inputs {
+            bf.emit(var_cell(&Variable::X(n)), ',');
+        }
+
+        bf.inc(RUN);
+        bf.inc(FLAGS);
+        bf.open(RUN);
+
+        for (pc, instruction) in self.instructions.iter().enumerate() {
+            let flag = FLAGS + pc;
+            let next = flag + 1;
+
+            bf.open(flag);
+            bf.dec(flag);
+            match instruction {
+                Instruction::Increment { var } => {
+                    bf.inc(var_cell(var));
+                    bf.inc(next);
+                }
+                Instruction::Decrement { var } => {
+                    let var = var_cell(var);
+                    bf.copy(var, TEMP_A);
+                    bf.open(TEMP_A);
+                    bf.dec(var);
+                    bf.clear(TEMP_A);
+                    bf.close(TEMP_A);
+                    bf.inc(next);
+                }
+                Instruction::JumpNonZero { var, to } => {
+                    // On jump to undefined label, halt execution
+                    let target = self.labels.get(to).map_or(halt, |&i| FLAGS + i);
+
+                    // temp a holds the condition, temp b is set if the jump isn't taken
+                    bf.copy(var_cell(var), TEMP_A);
+                    bf.inc(TEMP_B);
+                    bf.open(TEMP_A);
+                    bf.inc(target);
+                    bf.dec(TEMP_B);
+                    bf.clear(TEMP_A);
+                    bf.close(TEMP_A);
+                    bf.open(TEMP_B);
+                    bf.inc(next);
+                    bf.dec(TEMP_B);
+                    bf.close(TEMP_B);
+                }
+                Instruction::Nop => bf.inc(next),
+                Instruction::Print { var: _ } | Instruction::State => {
+                    return Err(CodegenError::new(
+                        "Meta-instructions have no brainfuck equivalent",
+                        pc,
+                    ));
+                }
+            }
+            bf.close(flag);
+        }
+
+        bf.open(halt);
+        bf.dec(halt);
+        bf.dec(RUN);
+        bf.close(halt);
+        bf.close(RUN);
+
+        bf.emit(var_cell(&Variable::Y), '.');
+        Ok(bf.code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal brainfuck interpreter with wrapping 8-bit cells and an unbounded tape
+    fn run_brainfuck(code: &str, input: &[u8]) -> Vec<u8> {
+        let code: Vec<char> = code.chars().collect();
+        let mut jumps = vec![0; code.len()];
+        let mut open = Vec::new();
+        for (i, &c) in code.iter().enumerate() {
+            match c {
+                '[' => open.push(i),
+                ']' => {
+                    let j = open.pop().expect("unbalanced ]");
+                    jumps[i] = j;
+                    jumps[j] = i;
+                }
+                _ => {}
+            }
+        }
+        assert!(open.is_empty(), "unbalanced [");
+
+        let (mut tape, mut ptr, mut pc) = (vec![0u8; 1], 0, 0);
+        let mut input = input.iter();
+        let mut output = Vec::new();
+        while pc < code.len() {
+            match code[pc] {
+                '+' => tape[ptr] = tape[ptr].wrapping_add(1),
+                '-' => tape[ptr] = tape[ptr].wrapping_sub(1),
+                '>' => {
+                    ptr += 1;
+                    if ptr == tape.len() { tape.push(0); }
+                }
+                '<' => ptr -= 1,
+                ',' => tape[ptr] = input.next().copied().unwrap_or(0),
+                '.' => output.push(tape[ptr]),
+                '[' if tape[ptr] == 0 => pc = jumps[pc],
+                ']' if tape[ptr] != 0 => pc = jumps[pc],
+                _ => {}
+            }
+            pc += 1;
+        }
+        output
+    }
+
+    fn compile(source: &str) -> Result<String, CodegenError> {
+        Program::from_reader(source.as_bytes(), &[]).unwrap().to_brainfuck()
+    }
+
+    #[test]
+    fn addition() {
+        let code = compile("y <- x1 + x2").unwrap();
+        assert_eq!(run_brainfuck(&code, &[3, 4]), vec![7]);
+        assert_eq!(run_brainfuck(&code, &[0, 0]), vec![0]);
+    }
+
+    #[test]
+    fn subtraction_saturates_at_zero() {
+        let code = compile("y <- x1 - x2").unwrap();
+        assert_eq!(run_brainfuck(&code, &[7, 3]), vec![4]);
+        assert_eq!(run_brainfuck(&code, &[2, 5]), vec![0]);
+    }
+
+    #[test]
+    fn jump_to_undefined_label_halts() {
+        let code = compile("y <- y + 1\nif y != 0 goto E9\ny <- y + 1").unwrap();
+        assert_eq!(run_brainfuck(&code, &[]), vec![1]);
+    }
+
+    #[test]
+    fn meta_instructions_are_rejected() {
+        assert!(compile("y <- y + 1\nprint y").is_err());
+        assert!(compile("state").is_err());
+    }
+}
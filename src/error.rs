@@ -1,3 +1,4 @@
+use crate::program::Label;
 use std::error::Error;
 use std::fmt::Display;
 
@@ -5,6 +6,7 @@ use std::fmt::Display;
 pub struct ParseError {
     message: String,
     line_number: usize,
+    module: Option<String>,
 }
 
 impl ParseError {
@@ -12,18 +14,108 @@ impl ParseError {
         ParseError {
             message: message.to_owned(),
             line_number,
+            module: None,
         }
     }
 
     pub fn boxed(message: &str, line_number: usize) -> Box<Self> {
         Box::new(Self::new(message, line_number))
     }
+
+    pub fn empty_macro_expansion(pattern: &str, line_number: usize) -> Self {
+        Self::new(&format!("Macro {} expanded to no instructions", pattern), line_number)
+    }
+
+    pub fn jump_into_macro_expansion(label: Label, line_number: usize) -> Self {
+        Self::new(
+            &format!("Jump to label {}, which is internal to a macro expansion", label),
+            line_number,
+        )
+    }
+
+    /// Mark the error as coming from an imported module, unless it already came from one of
+    /// that module's imports
+    pub fn in_module(mut self, module: &str) -> Self {
+        self.module.get_or_insert_with(|| module.to_owned());
+        self
+    }
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ParseError [line {}]: {}", self.line_number, self.message)
+        match &self.module {
+            Some(module) => write!(
+                f,
+                "ParseError [module {}, line {}]: {}",
+                module, self.line_number, self.message,
+            ),
+            None => write!(f, "ParseError [line {}]: {}", self.line_number, self.message),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug)]
+pub struct ParseWarning {
+    message: String,
+    line_number: usize,
+    module: Option<String>,
+}
+
+impl ParseWarning {
+    pub fn new(message: &str, line_number: usize) -> Self {
+        ParseWarning {
+            message: message.to_owned(),
+            line_number,
+            module: None,
+        }
+    }
+
+    pub fn unused_macro_parameter(param: &str, pattern: &str, line_number: usize) -> Self {
+        Self::new(&format!("Unused parameter {} in macro {}", param, pattern), line_number)
+    }
+
+    /// Mark the warning as coming from an imported module, unless it already came from one of
+    /// that module's imports
+    pub fn in_module(mut self, module: &str) -> Self {
+        self.module.get_or_insert_with(|| module.to_owned());
+        self
+    }
+}
+
+impl Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.module {
+            Some(module) => write!(
+                f,
+                "Warning [module {}, line {}]: {}",
+                module, self.line_number, self.message,
+            ),
+            None => write!(f, "Warning [line {}]: {}", self.line_number, self.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CodegenError {
+    message: String,
+    instruction: usize,
+}
+
+impl CodegenError {
+    pub fn new(message: &str, instruction: usize) -> Self {
+        CodegenError {
+            message: message.to_owned(),
+            instruction,
+        }
+    }
+}
+
+impl Display for CodegenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CodegenError [instruction {}]: {}", self.instruction, self.message)
     }
 }
 
-impl Error for ParseError {}
\ No newline at end of file
+impl Error for CodegenError {}
\ No newline at end of file
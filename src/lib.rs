@@ -0,0 +1,7 @@
+pub mod program;
+pub mod bigint;
+pub mod machine;
+pub mod error;
+pub mod output;
+mod prologue;
+mod codegen;
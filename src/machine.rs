@@ -1,3 +1,4 @@
+use crate::output::{OutputFormat, OutputValue};
 use crate::program::{Instruction, Program, Variable};
 
 pub struct State {
@@ -8,8 +9,12 @@ pub struct State {
 }
 
 impl State {
+    pub fn new(x: Vec<usize>, z: Vec<usize>, y: usize, pc: usize) -> Self {
+        State { x, z, y, pc }
+    }
+
     pub fn from_vars(vars: Vec<usize>) -> Self {
-        State { x: vars, z: Vec::new(), y: 0, pc: 0 }
+        State::new(vars, Vec::new(), 0, 0)
     }
 
     pub fn get_var(&self, var: &Variable) -> usize {
@@ -38,11 +43,23 @@ impl State {
 pub struct Machine<'a> {
     state: State,
     program: &'a Program,
+    format: OutputFormat,
 }
 
 impl<'a> Machine<'a> {
     pub fn new(initial_state: State, program: &'a Program) -> Self {
-        Machine { state: initial_state, program }
+        Machine { state: initial_state, program, format: OutputFormat::Dec }
+    }
+
+    /// Create a machine that starts execution at instruction pc, regardless of the initial state's
+    /// program counter
+    pub fn new_at_pc(initial_state: State, program: &'a Program, pc: usize) -> Self {
+        Machine { state: State { pc, ..initial_state }, program, format: OutputFormat::Dec }
+    }
+
+    /// Set the format used to print variable values
+    pub fn set_format(&mut self, format: OutputFormat) {
+        self.format = format;
     }
 
     pub fn state(&self) -> &State {
@@ -65,11 +82,15 @@ impl<'a> Machine<'a> {
                     jumped = true;
                 },
                 Instruction::Nop => {}
-                Instruction::Print { var } =>
-                    println!("[{}] {} = {}", self.state.pc, var, self.state.get_var(var)),
+                Instruction::Print { var } => println!(
+                    "[{}] {} = {}",
+                    self.state.pc,
+                    var,
+                    OutputValue(self.state.get_var(var), self.format),
+                ),
                 Instruction::State => {
                     println!("PC = {}", self.state.pc);
-                    println!("y = {}", self.state.get_var(&Variable::Y));
+                    println!("y = {}", OutputValue(self.state.get_var(&Variable::Y), self.format));
                     let rows = usize::max(self.state.x.len(), self.state.z.len());
                     println!("      |{:12}|{:12}", "x", "z");
                     println!("------|------------|------------");
@@ -77,8 +98,8 @@ impl<'a> Machine<'a> {
                         println!(
                             "{:6}|{:12}|{:12}",
                             i,
-                            self.state.get_var(&Variable::X(i)),
-                            self.state.get_var(&Variable::Z(i)),
+                            OutputValue(self.state.get_var(&Variable::X(i)), self.format),
+                            OutputValue(self.state.get_var(&Variable::Z(i)), self.format),
                         );
                     }
                 }
@@ -91,4 +112,23 @@ impl<'a> Machine<'a> {
     pub fn run(&mut self) {
         while self.state.pc < self.program.instructions.len() { self.step(); }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_starts_at_given_pc() {
+        let program = Program::from_reader("y <- y + 1\nz1 <- z1 + 1\ny <- y + 1".as_bytes(), &[]).unwrap();
+
+        // The state's own pc points at the first instruction, but execution starts at the third
+        let mut machine = Machine::new_at_pc(State::new(vec![4], Vec::new(), 0, 0), &program, 2);
+        assert_eq!(machine.state().pc, 2);
+
+        machine.run();
+        assert_eq!(machine.state().pc, 3);
+        assert_eq!(machine.state().get_var(&Variable::Y), 1);
+        assert_eq!(machine.state().get_var(&Variable::Z(1)), 0);
+        assert_eq!(machine.state().get_var(&Variable::X(1)), 4);
+    }
+}
@@ -1,37 +1,107 @@
-use crate::machine::{Machine, State};
-use crate::program::{Program, Variable};
+use slang::bigint::BigUint;
+use slang::machine::{Machine, State};
+use slang::output::{OutputFormat, OutputValue};
+use slang::program::{Program, Variable};
 use std::env;
 use std::error::Error;
 use std::fs::File;
-
-mod program;
-mod machine;
-mod error;
-mod prologue;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut args = env::args().skip(1).peekable();
     
     let mut print_code = false;
-    if args.peek().is_some_and(|arg| arg == "-p") {
-        args.next();
-        print_code = true;
+    let mut brainfuck = false;
+    let mut macro_order = false;
+    let mut decode = None;
+    let mut format = OutputFormat::Dec;
+    let mut lib_path = Vec::new();
+    while let Some(arg) = args.next_if(|arg| arg.starts_with('-') && arg != "-") {
+        match arg.as_str() {
+            "-p" => print_code = true,
+            "-b" => brainfuck = true,
+            "--macro-order" => macro_order = true,
+            "--format" => {
+                format = OutputFormat::parse(&args.next().ok_or("Missing format for --format")?)?;
+            }
+            "--lib-path" => {
+                lib_path.push(PathBuf::from(args.next().ok_or("Missing path for --lib-path")?));
+            }
+            "--decode" => {
+                let number = args.next().ok_or("Missing program number for --decode")?;
+                decode = Some(BigUint::parse(&number).ok_or("Invalid program number")?);
+            }
+            _ => return Err(format!("Unknown option {}", arg).into()),
+        }
     }
-    
-    let program_file = File::open(args.next().unwrap())?;
-    match Program::from_file(&program_file) {
+
+    // Search for modules in the paths given as options first, then in SLANG_PATH
+    if let Some(paths) = env::var_os("SLANG_PATH") {
+        lib_path.extend(env::split_paths(&paths));
+    }
+
+    // Decode program from its number if given, else read it from stdin if the file name is -
+    let program = if let Some(number) = &decode {
+        Program::from_number(number)
+    } else {
+        let program_path = args.next().unwrap();
+        if program_path == "-" {
+            Program::from_reader(io::stdin().lock(), &lib_path)
+        } else {
+            Program::from_file(&File::open(program_path)?, &lib_path)
+        }
+    };
+
+    match program {
         Ok(program) => {
-            if print_code {
+            // Warnings go to stderr so they don't mix with program output, highlighted on a terminal
+            let highlight = io::stderr().is_terminal();
+            for warning in &program.warnings {
+                if highlight {
+                    eprintln!("\x1b[33;1m{}\x1b[0m", warning);
+                } else {
+                    eprintln!("{}", warning);
+                }
+            }
+
+            if macro_order {
+                for m in &program.macros {
+                    println!("{:4}  {}", m.specificity, m.source);
+                }
+            }
+
+            if let (true, Some(number)) = (print_code, &decode) {
+                // Print the decoded program and check it encodes back to the same number
+                println!("Program number: {}", number);
+                for (i, instruction) in program.instructions.iter().enumerate() {
+                    let label = program.label_at_instruction(i)
+                        .map_or(String::new(), |label| format!("[{}]", label));
+                    println!("{:8}{}", label, instruction);
+                }
+
+                if program.has_number(number) {
+                    println!("Re-encoded program number matches");
+                } else {
+                    println!("\x1b[31;1mRe-encoded program number {} does not match\x1b[0m", program);
+                }
+            } else if print_code {
                 println!("Program number: {}", program);
+            } else if brainfuck {
+                match program.to_brainfuck() {
+                    Ok(code) => println!("{}", code),
+                    Err(e) => println!("\x1b[31;1m{}\x1b[0m", e),
+                }
             } else {
                 let mut machine = Machine::new(
                     State::from_vars(args.map(|arg| arg.parse::<usize>().unwrap()).collect()),
                     &program,
                 );
 
+                machine.set_format(format);
                 machine.run();
 
-                println!("Y = {}", machine.state().get_var(&Variable::Y));
+                println!("Y = {}", OutputValue(machine.state().get_var(&Variable::Y), format));
             }
         }
         Err(e) => {
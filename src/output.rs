@@ -0,0 +1,68 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    Dec,
+    Hex,
+    Bin,
+    Oct,
+}
+
+impl OutputFormat {
+    pub fn parse(format: &str) -> Result<Self, Box<dyn Error>> {
+        match format {
+            "dec" => Ok(OutputFormat::Dec),
+            "hex" => Ok(OutputFormat::Hex),
+            "bin" => Ok(OutputFormat::Bin),
+            "oct" => Ok(OutputFormat::Oct),
+            _ => Err(format!("Unknown output format {}, expected dec, hex, bin or oct", format).into()),
+        }
+    }
+}
+
+/// Variable value formatted in the given base. Padding and alignment work as they do for integers.
+pub struct OutputValue(pub usize, pub OutputFormat);
+
+impl Display for OutputValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let OutputValue(value, format) = self;
+        let digits = match format {
+            OutputFormat::Dec => value.to_string(),
+            OutputFormat::Hex => format!("{:#x}", value),
+            OutputFormat::Bin => format!("{:#b}", value),
+            OutputFormat::Oct => format!("{:#o}", value),
+        };
+        f.pad_integral(true, "", &digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_in_each_base() {
+        let formats = ["dec", "hex", "bin", "oct"].map(|format| OutputFormat::parse(format).unwrap());
+        let expected = ["255", "0xff", "0b11111111", "0o377"];
+        for (format, expected) in formats.into_iter().zip(expected) {
+            assert_eq!(OutputValue(255, format).to_string(), expected);
+        }
+        assert_eq!(OutputValue(0, OutputFormat::Hex).to_string(), "0x0");
+    }
+
+    #[test]
+    fn values_are_padded_like_integers() {
+        assert_eq!(format!("{:12}", OutputValue(255, OutputFormat::Dec)), "         255");
+        assert_eq!(format!("{:12}", OutputValue(255, OutputFormat::Hex)), "        0xff");
+        assert_eq!(format!("{:12}", OutputValue(5, OutputFormat::Bin)), "       0b101");
+        assert_eq!(format!("{:<12}|", OutputValue(8, OutputFormat::Oct)), "0o10        |");
+    }
+
+    #[test]
+    fn unknown_format_is_rejected() {
+        let err = OutputFormat::parse("decimal").err().unwrap().to_string();
+        assert!(err.contains("Unknown output format decimal"), "{}", err);
+        assert!(OutputFormat::parse("HEX").is_err());
+    }
+}
@@ -1,17 +1,29 @@
-use crate::error::ParseError;
+use crate::bigint::BigUint;
+use crate::error::{ParseError, ParseWarning};
 use crate::prologue::PROLOGUE;
 use fancy_regex::{Captures, Regex};
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Compile a constant regex the first time it's used, and reuse it after that
+macro_rules! static_regex {
+    ($pattern:expr) => {{
+        static REGEX: OnceLock<Regex> = OnceLock::new();
+        REGEX.get_or_init(|| Regex::new($pattern).unwrap())
+    }};
+}
 
 // =================================================================================================
 // Variables
 // =================================================================================================
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Variable {
     X(usize),
     Y,
@@ -22,6 +34,10 @@ impl Variable {
     pub fn parse(var: &str, line_num: usize) -> Result<Self, Box<dyn Error>> {
         match var.chars().next() {
             Some('x') => Ok(Variable::X(var[1..].parse()?)),
+            Some('y') if var.len() > 1 => Err(ParseError::boxed(
+                "Y is the single output variable and takes no index; did you mean 'y'?",
+                line_num,
+            )),
             Some('y') => Ok(Variable::Y),
             Some('z') => Ok(Variable::Z(var[1..].parse()?)),
             _ => Err(ParseError::boxed("Invalid variable name", line_num))
@@ -35,6 +51,14 @@ impl Variable {
             Variable::Z(num) => num * 2,
         }
     }
+
+    pub fn from_number(number: usize) -> Self {
+        match number {
+            0 => Variable::Y,
+            n if n % 2 == 1 => Variable::X(n.div_ceil(2)),
+            n => Variable::Z(n / 2),
+        }
+    }
 }
 
 impl Display for Variable {
@@ -78,6 +102,10 @@ impl Label {
     pub fn get_number(&self) -> usize {
         self.0 + 1
     }
+
+    pub fn from_number(number: usize) -> Self {
+        Label(number - 1)
+    }
 }
 
 impl Display for Label {
@@ -92,6 +120,7 @@ impl Display for Label {
 // Instructions
 // =================================================================================================
 
+#[derive(Debug, Eq, PartialEq)]
 pub enum Instruction {
     Increment { var: Variable },
     Decrement { var: Variable },
@@ -101,43 +130,71 @@ pub enum Instruction {
     State,
 }
 
-impl Instruction {
-    pub fn parse(instruction: &str, line_num: usize) -> Result<Option<Self>, Box<dyn Error>> {
-        let inc_regex: Regex = Regex::new(r"^(y|[xz]\d+) <- \1 \+ 1$").unwrap();
-        if let Some(caps) = inc_regex.captures(instruction)? {
-            let instruction = Instruction::Increment { var: Variable::parse(&caps[1], line_num)? };
-            return Ok(Some(instruction));
-        }
-
-        let dec_regex: Regex = Regex::new(r"^(y|[xz]\d+) <- (\1) - 1$").unwrap();
-        if let Some(caps) = dec_regex.captures(instruction)? {
-            let instruction = Instruction::Decrement { var: Variable::parse(&caps[1], line_num)? };
-            return Ok(Some(instruction));
-        }
+/// Kind of instruction, as identified by its first token
+enum InstructionKind {
+    Assign,
+    Jump,
+    Nop,
+    Print,
+    State,
+}
 
-        let jnz_regex: Regex = Regex::new(r"^if (y|[xz]\d+) != 0 goto (\w+)$").unwrap();
-        if let Some(caps) = jnz_regex.captures(instruction)? {
-            let instruction = Instruction::JumpNonZero {
-                var: Variable::parse(&caps[1], line_num)?,
-                to: Label::parse(&caps[2], line_num)?,
-            };
-            return Ok(Some(instruction));
+impl InstructionKind {
+    fn of(instruction: &str) -> Option<Self> {
+        match instruction.split(' ').next()? {
+            "if" => Some(InstructionKind::Jump),
+            "nop" => Some(InstructionKind::Nop),
+            "print" => Some(InstructionKind::Print),
+            "state" => Some(InstructionKind::State),
+            token if token.starts_with(['x', 'y', 'z']) => Some(InstructionKind::Assign),
+            _ => None,
         }
+    }
+}
 
-        let nop_regex: Regex = Regex::new(r"^nop$").unwrap();
-        if nop_regex.captures(instruction)?.is_some() {
-            return Ok(Some(Instruction::Nop));
-        }
+impl Instruction {
+    pub fn parse(instruction: &str, line_num: usize) -> Result<Option<Self>, Box<dyn Error>> {
+        let Some(kind) = InstructionKind::of(instruction) else {
+            return Ok(None);
+        };
 
-        let print_regex: Regex = Regex::new(r"^print (y|[xz]\d+)$").unwrap();
-        if let Some(caps) = print_regex.captures(instruction)? {
-            let instruction = Instruction::Print { var: Variable::parse(&caps[1], line_num)? };
-            return Ok(Some(instruction));
-        }
+        match kind {
+            InstructionKind::Assign => {
+                let inc_regex: &Regex = static_regex!(r"^(y\d*|[xz]\d+) <- \1 \+ 1$");
+                if let Some(caps) = inc_regex.captures(instruction)? {
+                    let instruction = Instruction::Increment { var: Variable::parse(&caps[1], line_num)? };
+                    return Ok(Some(instruction));
+                }
 
-        let state_regex: Regex = Regex::new(r"^state$").unwrap();
-        if state_regex.captures(instruction)?.is_some() {
-            return Ok(Some(Instruction::State));
+                let dec_regex: &Regex = static_regex!(r"^(y\d*|[xz]\d+) <- \1 - 1$");
+                if let Some(caps) = dec_regex.captures(instruction)? {
+                    let instruction = Instruction::Decrement { var: Variable::parse(&caps[1], line_num)? };
+                    return Ok(Some(instruction));
+                }
+            }
+            InstructionKind::Jump => {
+                let jnz_regex: &Regex = static_regex!(r"^if (y\d*|[xz]\d+) != 0 goto (\w+)$");
+                if let Some(caps) = jnz_regex.captures(instruction)? {
+                    let instruction = Instruction::JumpNonZero {
+                        var: Variable::parse(&caps[1], line_num)?,
+                        to: Label::parse(&caps[2], line_num)?,
+                    };
+                    return Ok(Some(instruction));
+                }
+            }
+            InstructionKind::Nop => if instruction == "nop" {
+                return Ok(Some(Instruction::Nop));
+            },
+            InstructionKind::Print => {
+                let print_regex: &Regex = static_regex!(r"^print (y\d*|[xz]\d+)$");
+                if let Some(caps) = print_regex.captures(instruction)? {
+                    let instruction = Instruction::Print { var: Variable::parse(&caps[1], line_num)? };
+                    return Ok(Some(instruction));
+                }
+            }
+            InstructionKind::State => if instruction == "state" {
+                return Ok(Some(Instruction::State));
+            },
         }
 
         Ok(None)
@@ -152,6 +209,16 @@ impl Instruction {
             _ => panic!("Attempted to get number for meta-instruction"),
         }
     }
+
+    pub fn from_number(b: usize, c: usize) -> Self {
+        let var = Variable::from_number(c);
+        match b {
+            0 => Instruction::Nop,
+            1 => Instruction::Increment { var },
+            2 => Instruction::Decrement { var },
+            b => Instruction::JumpNonZero { var, to: Label::from_number(b - 2) },
+        }
+    }
 }
 
 impl Display for Instruction {
@@ -172,17 +239,26 @@ impl Display for Instruction {
 // =================================================================================================
 
 pub struct Macro {
+    pub source: String,
+    pub specificity: usize,
     pub pattern: Regex,
     pub replacements: HashMap<String, usize>,
     pub instructions: Vec<String>,
+    pub line_num: usize,
+    pub suppress_warnings: bool,
 }
 
 impl Macro {
-    pub fn parse(def: &str) -> Self {
-        let escape_regex: Regex = Regex::new(r"[+*.$^()|?\\\[\]]").unwrap();
+    pub fn parse(def: &str, line_num: usize) -> Self {
+        let source = def.to_string();
+
+        // Specificity is the number of literal (non-parameter) characters in the pattern
+        let macro_def_regex: &Regex = static_regex!(r"\{(\w+)}");
+        let specificity = macro_def_regex.replace_all(def, "").chars().count();
+
+        let escape_regex: &Regex = static_regex!(r"[+*.$^()|?\\\[\]]");
         let def = escape_regex.replace_all(def, |caps: &Captures| format!(r"\{}", &caps[0]));
 
-        let macro_def_regex: Regex = Regex::new(r"\{(\w+)}").unwrap();
         let pattern = macro_def_regex.replace_all(&def, r"(\w+)");
         let pattern = Regex::new(&format!("^{}$", pattern)).unwrap();
 
@@ -191,7 +267,96 @@ impl Macro {
             replacements.insert(caps[1].to_string(), n);
         }
 
-        Macro { pattern, replacements, instructions: Vec::new() }
+        Macro {
+            source,
+            specificity,
+            pattern,
+            replacements,
+            instructions: Vec::new(),
+            line_num,
+            suppress_warnings: false,
+        }
+    }
+
+    /// Parameters that never appear in the macro definition, in pattern order. Parameters are
+    /// replaced wherever their name appears when the macro is expanded, even inside other words,
+    /// so they are matched the same way here.
+    pub fn unused_parameters(&self) -> Vec<&str> {
+        let mut unused: Vec<_> = self.replacements.iter()
+            .filter(|(param, _)| !self.instructions.iter().any(|line| line.contains(param.as_str())))
+            .collect();
+        unused.sort_by_key(|(_, &n)| n);
+        unused.into_iter().map(|(param, _)| param.as_str()).collect()
+    }
+}
+
+// =================================================================================================
+// Modules
+// =================================================================================================
+
+pub struct Module {
+    pub name: String,
+    pub macros: Vec<Macro>,
+    pub warnings: Vec<ParseWarning>,
+}
+
+impl Module {
+    /// Load a module from the first directory in lib_path containing a file named after it, with
+    /// the .s extension. Module files may only contain macro definitions. The names of modules
+    /// currently being loaded are kept in loading, to detect circular imports, and the names of
+    /// modules already loaded, including this one and its imports, are added to loaded.
+    pub fn load(
+        name: &str,
+        lib_path: &[PathBuf],
+        loading: &mut Vec<String>,
+        loaded: &mut Vec<String>,
+        line_num: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        if let Some(start) = loading.iter().position(|module| module == name) {
+            let cycle: Vec<_> = loading[start..].iter().map(String::as_str).chain([name]).collect();
+            return Err(ParseError::boxed(
+                &format!("Circular import {}", cycle.join(" -> ")),
+                line_num,
+            ));
+        }
+
+        let Some(path) = lib_path.iter()
+            .map(|dir| dir.join(format!("{}.s", name)))
+            .find(|path| path.is_file()) else {
+            return Err(ParseError::boxed(&format!("Module {} not found", name), line_num));
+        };
+
+        loading.push(name.to_string());
+        let reader = BufReader::new(File::open(&path)?);
+        let program = Program::parse(reader, lib_path, loading, loaded, "", 1);
+        loading.pop();
+
+        // Point errors in the module's source at the module, since their line numbers are in it
+        let program = program.map_err(|err| -> Box<dyn Error> {
+            match err.downcast::<ParseError>() {
+                Ok(err) => Box::new(err.in_module(name)),
+                Err(err) => ParseError::boxed(&format!("In module {}: {}", name, err), line_num),
+            }
+        })?;
+
+        if program.module.as_deref() != Some(name) {
+            return Err(ParseError::boxed(
+                &format!("File {} does not declare @module {}", path.display(), name),
+                line_num,
+            ));
+        }
+        if !program.instructions.is_empty() {
+            return Err(ParseError::boxed(
+                &format!("Module {} contains instructions outside of macro definitions", name),
+                line_num,
+            ));
+        }
+
+        loaded.push(name.to_string());
+        let warnings = program.warnings.into_iter()
+            .map(|warning| warning.in_module(name))
+            .collect();
+        Ok(Module { name: name.to_string(), macros: program.macros, warnings })
     }
 }
 
@@ -199,77 +364,178 @@ impl Macro {
 // Parser
 // =================================================================================================
 
+/// Largest program number, in bits, that will be computed in full
+const MAX_PROGRAM_NUMBER_BITS: usize = 1 << 16;
+
 pub struct Program {
     pub instructions: Vec<Instruction>,
     pub labels: HashMap<Label, usize>,
     pub macros: Vec<Macro>,
+    pub warnings: Vec<ParseWarning>,
+    pub module: Option<String>,
+    pub imports: Vec<String>,
     max_temp_var: usize,
     max_labels: [usize; 5],
+    generated_labels: HashSet<Label>,
+    user_jumps: Vec<(Label, usize)>,
 }
 
 impl Program {
-    pub fn from_file(file: &File) -> Result<Self, Box<dyn Error>> {
-        let mut program = Program {
+    fn new() -> Self {
+        Program {
             instructions: Vec::new(),
             labels: HashMap::new(),
             macros: Vec::new(),
+            warnings: Vec::new(),
+            module: None,
+            imports: Vec::new(),
             max_temp_var: 0,
             max_labels: [0; 5],
-        };
+            generated_labels: HashSet::new(),
+            user_jumps: Vec::new(),
+        }
+    }
+
+    /// Decode a program from its Gödel number. Decoding requires factoring the number, so it
+    /// will be very slow if number + 1 has any large prime factors.
+    pub fn from_number(number: &BigUint) -> Result<Self, Box<dyn Error>> {
+        let mut program = Program::new();
+
+        let mut n = number.add_one();
+        for p in primes() {
+            if n == BigUint::one() { break; }
+
+            let mut exponent = 0;
+            loop {
+                let (q, r) = n.div_rem(p as u32);
+                if r != 0 { break; }
+                n = q;
+                exponent += 1;
+            }
+
+            let (a, bc) = unpair(exponent);
+            let (b, c) = unpair(bc);
+            let line_num = program.instructions.len() + 1;
+            if a > 0 {
+                let label = Label::from_number(a);
+                if program.labels.contains_key(&label) {
+                    return Err(ParseError::boxed(&format!("Redefined label {}", label), line_num));
+                }
+                program.labels.insert(label, program.instructions.len());
+            }
+            if b == 0 && c != 0 {
+                // <0, c> encodes V <- V on a variable other than y, which has no representation
+                let var = Variable::from_number(c);
+                return Err(ParseError::boxed(
+                    &format!("Instruction {} <- {0} is not supported", var),
+                    line_num,
+                ));
+            }
+            program.instructions.push(Instruction::from_number(b, c));
+        }
+
+        Ok(program)
+    }
+
+    /// Parse a program from a file. Imported modules are searched for in the directories in
+    /// lib_path, in order.
+    pub fn from_file(file: &File, lib_path: &[PathBuf]) -> Result<Self, Box<dyn Error>> {
+        Self::from_reader(BufReader::new(file), lib_path)
+    }
+
+    pub fn from_reader(reader: impl BufRead, lib_path: &[PathBuf]) -> Result<Self, Box<dyn Error>> {
+        let mut imports = Vec::new();
+        let mut program = Self::parse(reader, lib_path, &mut Vec::new(), &mut imports, PROLOGUE, 0)?;
+        program.imports = imports;
+        Ok(program)
+    }
+
+    fn parse(
+        reader: impl BufRead,
+        lib_path: &[PathBuf],
+        loading: &mut Vec<String>,
+        loaded: &mut Vec<String>,
+        prologue: &str,
+        first_line_num: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut program = Program::new();
         let mut current_macro: Option<Box<Macro>> = None;
 
-        // Read source file and append its lines to prologue
-        let reader = BufReader::new(file);
-        let lines: Vec<_> = PROLOGUE.lines()
+        // Read source and append its lines to prologue, numbering them from first_line_num
+        let lines: Vec<_> = prologue.lines()
             .map(|str| str.to_string())
             .chain(reader.lines().map_while(Result::ok))
+            .map(strip_comment)
             .enumerate()
+            .map(|(n, line)| (n + first_line_num, line))
             .collect();
+        let lines = join_continued_lines(lines);
 
-        // Variable and label counting pre-pass
-        let var_regex: Regex = Regex::new(r"\bz(\d+)\b").unwrap();
-        let label_regex: Regex = Regex::new(r"([A-E])(\d+)").unwrap();
-        for (_, line) in &lines {
-            program.max_temp_var = var_regex.captures_iter(line).flatten()
-                .map(|caps| caps[1].parse::<usize>().unwrap())
-                .fold(program.max_temp_var, usize::max);
+        // Load imported modules before processing the rest of the source, as if their macros
+        // were defined at the start of it. Each module is only loaded once, even if it's imported
+        // from more than one place.
+        for (line_num, line) in &lines {
+            if let ("@import", name) = split_directive(line) {
+                if loaded.iter().any(|module| module == name) {
+                    continue;
+                }
 
-            program.max_labels = label_regex.captures_iter(line).flatten()
-                .map(|caps| parse_label_capture(&caps))
-                .fold(program.max_labels, |labels, (group, number)| {
-                    if number > labels[group] {
-                        let mut new_labels = labels;
-                        new_labels[group] = number;
-                        new_labels
-                    } else {
-                        labels
-                    }
-                })
+                let module = Module::load(name, lib_path, loading, loaded, *line_num)?;
+                program.reserve_names(
+                    module.macros.iter().flat_map(|m| &m.instructions).map(String::as_str),
+                );
+                program.macros.extend(module.macros);
+                program.warnings.extend(module.warnings);
+            }
         }
+        program.macros.sort_by_key(|m| Reverse(m.specificity));
+
+        program.reserve_names(lines.iter().map(|(_, line)| line.as_str()));
 
         for (line_num, line) in lines {
-            if line.is_empty() || line.starts_with('#') {
+            if line.trim().is_empty() {
                 continue; // Skip empty lines and comments
             }
 
             if line.starts_with('@') {
                 // Process directives:
-                if let Some(line) = line.strip_prefix("@def") {
-                    if current_macro.is_some() {
+                match split_directive(&line) {
+                    ("@def", pattern) => if current_macro.is_some() {
                         return Err(ParseError::boxed("Unexpected nested @def directive", line_num));
                     } else {
-                        current_macro = Some(Box::new(Macro::parse(line.trim())));
-                    }
-                } else if line.starts_with("@end") {
-                    match current_macro {
+                        current_macro = Some(Box::new(Macro::parse(pattern, line_num)));
+                    },
+                    ("@end", _) => match current_macro {
                         Some(boxed_macro) => {
+                            if !boxed_macro.suppress_warnings {
+                                for param in boxed_macro.unused_parameters() {
+                                    program.warnings.push(ParseWarning::unused_macro_parameter(
+                                        param,
+                                        &boxed_macro.source,
+                                        boxed_macro.line_num,
+                                    ));
+                                }
+                            }
+
+                            // Keep macros sorted by specificity, so the most specific
+                            // pattern that matches is always expanded. Sort is stable, so
+                            // ties are still matched in definition order.
                             program.macros.push(*boxed_macro);
+                            program.macros.sort_by_key(|m| Reverse(m.specificity));
                             current_macro = None;
                         }
                         _ => return Err(ParseError::boxed("Unexpected @end directive", line_num)),
-                    }
-                } else {
-                    return Err(ParseError::boxed("Unknown directive", line_num));
+                    },
+                    ("@module", name) => program.module = Some(name.to_string()),
+                    ("@import", _) => {} // Already loaded before processing
+                    ("@suppress-warning", _) => match &mut current_macro {
+                        Some(current_macro) => current_macro.suppress_warnings = true,
+                        _ => return Err(ParseError::boxed(
+                            "Unexpected @suppress-warning directive",
+                            line_num,
+                        )),
+                    },
+                    _ => return Err(ParseError::boxed("Unknown directive", line_num)),
                 }
                 continue;
             }
@@ -281,9 +547,46 @@ impl Program {
             }
         }
 
+        program.validate()?;
         Ok(program)
     }
 
+    /// Check that user code never jumps to a label generated by a macro expansion, since that
+    /// would jump into the middle of the expanded macro
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        for (label, line_num) in &self.user_jumps {
+            if self.generated_labels.contains(label) {
+                return Err(Box::new(ParseError::jump_into_macro_expansion(*label, *line_num)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Variable and label counting pre-pass: keep track of the highest auxiliary variable and
+    /// label numbers in use, so automatic variables and labels never conflict with them
+    fn reserve_names<'a>(&mut self, lines: impl Iterator<Item = &'a str>) {
+        let var_regex: &Regex = static_regex!(r"\bz(\d+)\b");
+        let label_regex: &Regex = static_regex!(r"([A-E])(\d+)");
+        for line in lines {
+            self.max_temp_var = var_regex.captures_iter(line).flatten()
+                .map(|caps| caps[1].parse::<usize>().unwrap())
+                .fold(self.max_temp_var, usize::max);
+
+            self.max_labels = label_regex.captures_iter(line).flatten()
+                .map(|caps| parse_label_capture(&caps))
+                .fold(self.max_labels, |labels, (group, number)| {
+                    if number > labels[group] {
+                        let mut new_labels = labels;
+                        new_labels[group] = number;
+                        new_labels
+                    } else {
+                        labels
+                    }
+                })
+        }
+    }
+
     fn parse_line(&mut self, instruction: &str, line_num: usize) -> Result<(), Box<dyn Error>> {
         // Find a label and add it to the program's list of labels
         let instruction = find_label(
@@ -295,6 +598,9 @@ impl Program {
 
         // Match an instruction
         if let Some(instruction) = Instruction::parse(instruction, line_num)? {
+            if let Instruction::JumpNonZero { var: _, to } = instruction {
+                self.user_jumps.push((to, line_num));
+            }
             self.instructions.push(instruction);
             return Ok(());
         }
@@ -309,6 +615,9 @@ impl Program {
                     &mut self.labels,
                     &mut self.max_temp_var,
                     &mut self.max_labels,
+                    &mut self.generated_labels,
+                    &mut self.user_jumps,
+                    &[],
                     &caps,
                     line_num,
                 )?;
@@ -322,20 +631,60 @@ impl Program {
         )
     }
 
-    fn get_number(&self) -> Vec<usize> {
-        let mut reverse_labels = HashMap::new();
-        for (label, inst) in &self.labels {
-            reverse_labels.insert(*inst, *label);
+    pub fn instruction_at_label(&self, label: &Label) -> Option<&Instruction> {
+        self.labels.get(label).and_then(|&i| self.instructions.get(i))
+    }
+
+    /// Find the label marking an instruction, if any. If more than one label marks the same
+    /// instruction, returns the one with the lowest number.
+    pub fn label_at_instruction(&self, index: usize) -> Option<Label> {
+        self.labels.iter()
+            .filter(|(_, &i)| i == index)
+            .map(|(label, _)| *label)
+            .min_by_key(Label::get_number)
+    }
+
+    /// Gödel number of the program [#I1, ..., #In] - 1, that is, the product of the first n primes
+    /// raised to each instruction's number, minus one. Program numbers grow very quickly, so this
+    /// returns None if the number would be too large to compute.
+    pub fn number(&self) -> Option<BigUint> {
+        self.number_up_to(MAX_PROGRAM_NUMBER_BITS)
+    }
+
+    /// Check whether number is the Gödel number of the program, no matter how large it is
+    pub fn has_number(&self, number: &BigUint) -> bool {
+        // Allow one extra bit, since the estimated size is of number + 1
+        self.number_up_to(number.bits() + 1).as_ref() == Some(number)
+    }
+
+    /// Gödel number of the program, or None if it would take more than max_bits bits
+    fn number_up_to(&self, max_bits: usize) -> Option<BigUint> {
+        let exponents = self.get_number();
+        let primes: Vec<_> = primes().take(exponents.len()).collect();
+
+        // The product takes about the sum of exp * log2(p) bits
+        let estimated_bits = exponents.iter().zip(&primes).try_fold(0.0, |bits, (exp, &p)| {
+            Some(bits + exp.to_usize()? as f64 * (p as f64).log2())
+        })?;
+        if estimated_bits > max_bits as f64 {
+            return None;
         }
 
+        let number = exponents.iter().zip(&primes)
+            .map(|(exp, &p)| BigUint::from(p).pow(exp.to_usize().unwrap()))
+            .fold(BigUint::one(), |acc, factor| acc.mul(&factor));
+        Some(number.sub_one())
+    }
+
+    fn get_number(&self) -> Vec<BigUint> {
         let mut inst_idx = 0;
         self.instructions.iter().filter_map(|instruction| {
             let temp = match instruction {
                 Instruction::Print { var: _ } | Instruction::State => None, // Skip meta-instructions
                 instruction => {
                     let (b, c) = instruction.get_number();
-                    let a = reverse_labels.get(&inst_idx).map_or(0, |label| label.get_number());
-                    Some(pair(a, pair(b, c)))
+                    let a = self.label_at_instruction(inst_idx).map_or(0, |label| label.get_number());
+                    Some(pair(a, &pair(b, &BigUint::from(c))))
                 }
             };
             inst_idx += 1;
@@ -345,18 +694,71 @@ impl Program {
 }
 
 impl Display for Program {
+    /// Formats the program as its Gödel number, or as its list of prime exponents if the number
+    /// is too large to compute
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[{}]", self.get_number().iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "))
+        match self.number() {
+            Some(number) => write!(f, "{}", number),
+            None => write!(
+                f,
+                "[{}] - 1",
+                self.get_number().iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "),
+            ),
+        }
     }
 }
 
+/// Remove a comment, from the first # to the end of the line. Since # can't appear in variable
+/// names, labels or macro patterns, it always starts a comment.
+fn strip_comment(mut line: String) -> String {
+    if let Some(start) = line.find('#') {
+        line.truncate(start);
+    }
+    line
+}
+
+/// Split a directive line into the directive itself and the rest of the line, trimmed
+fn split_directive(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((directive, rest)) => (directive, rest.trim()),
+        None => (line.trim_end(), ""),
+    }
+}
+
+/// Join any line ending with a backslash with the line following it, keeping the line number of
+/// the first line
+fn join_continued_lines(lines: Vec<(usize, String)>) -> Vec<(usize, String)> {
+    let mut joined: Vec<(usize, String)> = Vec::new();
+    let mut continued = false;
+    for (line_num, line) in lines {
+        if continued {
+            let (_, last) = joined.last_mut().unwrap();
+            last.push(' ');
+            last.push_str(line.trim_start());
+        } else {
+            joined.push((line_num, line));
+        }
+
+        let (_, last) = joined.last_mut().unwrap();
+        continued = last.trim_end().ends_with('\\');
+        if continued {
+            let len = last.trim_end().len() - 1;
+            last.truncate(len);
+            let len = last.trim_end().len();
+            last.truncate(len);
+        }
+    }
+
+    joined
+}
+
 fn find_label<'a>(
     instruction: &'a str,
     instruction_number: usize,
     labels: &mut HashMap<Label, usize>,
     line_num: usize,
 ) -> Result<&'a str, Box<dyn Error>> {
-    let label_regex: Regex = Regex::new(r"^\[(\w+)]").unwrap();
+    let label_regex: &Regex = static_regex!(r"^\[(\w+)]");
     match label_regex.captures(instruction)? {
         Some(caps) => {
             let full = &caps[0];
@@ -373,6 +775,10 @@ fn find_label<'a>(
     }
 }
 
+/// Expand a macro call. Jumps in the expansion to any label other than the automatic labels of
+/// this expansion or of the ones enclosing it (enclosing_labels) are added to user_jumps, since
+/// their targets were written by the user, either in the call or in the macro definition.
+#[allow(clippy::too_many_arguments)]
 fn expand_macro(
     macros: &Vec<Macro>,
     m: &Macro,
@@ -380,13 +786,17 @@ fn expand_macro(
     labels: &mut HashMap<Label, usize>,
     max_temp_var: &mut usize,
     max_labels: &mut [usize; 5],
+    generated_labels: &mut HashSet<Label>,
+    user_jumps: &mut Vec<(Label, usize)>,
+    enclosing_labels: &[Label],
     caps: &Captures,
     line_num: usize,
 ) -> Result<(), Box<dyn Error>> {
-    let auto_var_regex: Regex = Regex::new(r"\$(\w+)").unwrap();
-    let auto_label_regex: Regex = Regex::new(r"%([A-E])(\d+)").unwrap();
+    let auto_var_regex: &Regex = static_regex!(r"\$(\w+)");
+    let auto_label_regex: &Regex = static_regex!(r"%([A-E])(\d+)");
     let mut auto_vars = HashMap::new();
     let mut auto_labels = HashMap::new();
+    let (instruction_count, label_count) = (instructions.len(), labels.len());
 
     for instruction in &m.instructions {
         // Replace automatic labels
@@ -395,7 +805,9 @@ fn expand_macro(
             let local = Label::new(group, number);
             let label = auto_labels.entry(local).or_insert_with(|| {
                 max_labels[group] += 1;
-                Label::new(group, max_labels[group])
+                let label = Label::new(group, max_labels[group]);
+                generated_labels.insert(label);
+                label
             });
 
             format!("{}", label)
@@ -427,8 +839,18 @@ fn expand_macro(
         });
 
         if let Some(instruction) = Instruction::parse(&instruction, line_num)? {
+            if let Instruction::JumpNonZero { var: _, to } = instruction {
+                let own_label = auto_labels.values().any(|&label| label == to);
+                if !own_label && !enclosing_labels.contains(&to) {
+                    user_jumps.push((to, line_num));
+                }
+            }
             instructions.push(instruction);
         } else {
+            let enclosing_labels: Vec<_> = enclosing_labels.iter()
+                .chain(auto_labels.values())
+                .copied()
+                .collect();
             for m in macros {
                 if let Some(caps) = m.pattern.captures(&instruction)? {
                     expand_macro(
@@ -438,6 +860,9 @@ fn expand_macro(
                         labels,
                         max_temp_var,
                         max_labels,
+                        generated_labels,
+                        user_jumps,
+                        &enclosing_labels,
                         &caps,
                         line_num,
                     )?;
@@ -447,6 +872,10 @@ fn expand_macro(
         }
     }
 
+    if instructions.len() == instruction_count && labels.len() == label_count {
+        return Err(Box::new(ParseError::empty_macro_expansion(&m.source, line_num)));
+    }
+
     Ok(())
 }
 
@@ -457,6 +886,513 @@ fn parse_label_capture(caps: &Captures) -> (usize, usize) {
     )
 }
 
-fn pair(x: usize, y: usize) -> usize {
-    2usize.pow(x as u32) * (2 * y + 1) - 1
+fn pair(x: usize, y: &BigUint) -> BigUint {
+    y.shl(1).add_one().shl(x).sub_one()
+}
+
+fn unpair(z: usize) -> (usize, usize) {
+    let x = (z + 1).trailing_zeros() as usize;
+    (x, ((z + 1) >> x) / 2)
+}
+
+fn primes() -> impl Iterator<Item = usize> {
+    let mut found: Vec<usize> = Vec::new();
+    (2..).filter(move |&n| {
+        let is_prime = found.iter().take_while(|&&p| p * p <= n).all(|&p| n % p != 0);
+        if is_prime { found.push(n); }
+        is_prime
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Program {
+        Program::from_reader(source.as_bytes(), &[]).unwrap()
+    }
+
+    #[test]
+    fn variables_compare_by_register() {
+        assert_eq!(Variable::X(1), Variable::X(1));
+        assert_ne!(Variable::X(1), Variable::X(2));
+        assert_ne!(Variable::X(1), Variable::Z(1));
+        assert_eq!(Variable::parse("z3", 1).unwrap(), Variable::Z(3));
+    }
+
+    #[test]
+    fn variables_as_hash_set_keys() {
+        let vars: std::collections::HashSet<_> = ["x1", "z1", "y", "x1", "x2", "y"].iter()
+            .map(|var| Variable::parse(var, 1).unwrap())
+            .collect();
+        assert_eq!(vars.len(), 4);
+        assert!(vars.contains(&Variable::X(1)));
+        assert!(vars.contains(&Variable::Y));
+        assert!(!vars.contains(&Variable::Z(2)));
+    }
+
+    fn parse_error(source: &str) -> String {
+        Program::from_reader(source.as_bytes(), &[]).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn indexed_output_variable_is_rejected() {
+        for source in ["y1 <- y1 + 1", "print y1", "if y1 != 0 goto A1"] {
+            let err = parse_error(source);
+            assert!(err.contains("Y is the single output variable"), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn instructions_round_trip_through_display() {
+        let indices = [1, 2, 9, 10, 11, 99, 100, 12345, usize::MAX / 2, usize::MAX];
+        let vars: Vec<_> = std::iter::once(Variable::Y)
+            .chain(indices.iter().flat_map(|&n| [Variable::X(n), Variable::Z(n)]))
+            .collect();
+        let labels: Vec<_> = (0..5)
+            .flat_map(|group| [1, 2, 9, 10, 11, 1000].map(|number| Label::new(group, number)))
+            .collect();
+
+        let mut instructions = vec![Instruction::Nop, Instruction::State];
+        for var in &vars {
+            instructions.push(Instruction::Increment { var: var.clone() });
+            instructions.push(Instruction::Decrement { var: var.clone() });
+            instructions.push(Instruction::Print { var: var.clone() });
+            for &to in &labels {
+                instructions.push(Instruction::JumpNonZero { var: var.clone(), to });
+            }
+        }
+
+        for instruction in instructions {
+            let source = instruction.to_string();
+            let parsed = Instruction::parse(&source, 1).unwrap();
+            assert_eq!(parsed, Some(instruction), "{}", source);
+        }
+    }
+
+    fn numbered(lines: &[&str]) -> Vec<(usize, String)> {
+        lines.iter().enumerate().map(|(n, line)| (n, line.to_string())).collect()
+    }
+
+    #[test]
+    fn continued_lines_keep_first_line_number() {
+        let lines = join_continued_lines(numbered(&["a", "b \\", "    c \\", "  d", "e"]));
+        assert_eq!(lines, vec![
+            (0, "a".to_string()),
+            (1, "b c d".to_string()),
+            (4, "e".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn trailing_backslash_on_last_line() {
+        let lines = join_continued_lines(numbered(&["y <- y + 1", "x1 <- x1 + 1 \\  "]));
+        assert_eq!(lines, vec![(0, "y <- y + 1".to_string()), (1, "x1 <- x1 + 1".to_string())]);
+        assert_eq!(parse("y <- y + 1\nx1 <- x1 + 1 \\").instructions.len(), 2);
+    }
+
+    #[test]
+    fn backslash_before_inline_comment() {
+        let continued = parse("z1 <- x1 + \\ # first operand\n      x2");
+        assert_eq!(continued.instructions, parse("z1 <- x1 + x2").instructions);
+    }
+
+    #[test]
+    fn continued_macro_call() {
+        let definition = concat!(
+            "@def accumulate into {v} the values {a}, then {b}, then {c}, then {d} and finally {e}\n",
+            "        v <- a + b\n",
+            "        $t <- v + c\n",
+            "        v <- $t + d\n",
+            "        $t <- v + e\n",
+            "        v <- $t\n",
+            "@end\n",
+        );
+        let long_call = "[A1]    accumulate into z10 the values x1, then x2, then x3, then x4 and finally x5";
+        let continued_call = concat!(
+            "[A1]    accumulate into z10 the values x1, \\\n",
+            "            then x2, then x3, \\\n",
+            "            then x4 and finally x5",
+        );
+        assert!(long_call.len() > 80);
+        assert!(continued_call.lines().all(|line| line.len() <= 80));
+
+        let long = parse(&format!("{}{}", definition, long_call));
+        let continued = parse(&format!("{}{}", definition, continued_call));
+        assert!(!continued.instructions.is_empty());
+        assert_eq!(continued.instructions, long.instructions);
+        assert_eq!(continued.labels, long.labels);
+    }
+
+    #[test]
+    fn strip_comment_keeps_code_before_hash() {
+        assert_eq!(strip_comment("y <- y + 1 # increment".to_string()), "y <- y + 1 ");
+        assert_eq!(strip_comment("# whole line".to_string()), "");
+        assert_eq!(strip_comment("[A1]#no space".to_string()), "[A1]");
+        assert_eq!(strip_comment("y <- y + 1".to_string()), "y <- y + 1");
+    }
+
+    #[test]
+    fn inline_comment_on_instruction_line() {
+        let commented = parse("x1 <- x1 - 1 # decrement\nif x1 != 0 goto A1    # loop");
+        assert_eq!(commented.instructions, parse("x1 <- x1 - 1\nif x1 != 0 goto A1").instructions);
+    }
+
+    #[test]
+    fn inline_comment_on_label_line() {
+        let commented = parse("[A1]    x1 <- x1 - 1 # loop start\n[B2]    if x1 != 0 goto A1#exit");
+        let plain = parse("[A1]    x1 <- x1 - 1\n[B2]    if x1 != 0 goto A1");
+        assert_eq!(commented.instructions, plain.instructions);
+        assert_eq!(commented.labels, plain.labels);
+        assert!(commented.labels.contains_key(&Label::new(0, 1)));
+        assert!(commented.labels.contains_key(&Label::new(1, 2)));
+    }
+
+    #[test]
+    fn inline_comment_in_macro_definition() {
+        let commented = parse(concat!(
+            "@def {v} <- double {a} # v = 2a\n",
+            "        v <- a + a     # sum with itself\n",
+            "        # full line comment inside the body\n",
+            "@end # end of double\n",
+            "z1 <- double x1 # call\n",
+        ));
+        let plain = parse(concat!(
+            "@def {v} <- double {a}\n",
+            "        v <- a + a\n",
+            "@end\n",
+            "z1 <- double x1\n",
+        ));
+        assert!(commented.warnings.is_empty());
+        assert!(!commented.instructions.is_empty());
+        assert_eq!(commented.instructions, plain.instructions);
+    }
+
+    #[test]
+    fn most_specific_macro_matches_first() {
+        let program = parse(concat!(
+            "@def {v} <- {a} {op} {b}\n",
+            "@suppress-warning\n",
+            "        v <- a\n",
+            "@end\n",
+            "@def {v} <- {a} plus {b}\n",
+            "        v <- a + b\n",
+            "@end\n",
+            "z1 <- x1 plus x2\n",
+        ));
+        assert_eq!(program.instructions, parse("z1 <- x1 + x2").instructions);
+    }
+
+    #[test]
+    fn equally_specific_macros_match_in_definition_order() {
+        let program = parse(concat!(
+            "@def {v} <- pick {a} {b}\n",
+            "@suppress-warning\n",
+            "        v <- a\n",
+            "@end\n",
+            "@def {v} <- pick {a} {b}\n",
+            "@suppress-warning\n",
+            "        v <- b\n",
+            "@end\n",
+            "y <- pick x1 x2\n",
+        ));
+        assert_eq!(program.instructions, parse("y <- x1").instructions);
+    }
+
+    #[test]
+    fn unused_parameter_matches_substitution() {
+        let program = parse(concat!(
+            "@def {v} <- pick {o} {a}\n",
+            "        v <- a\n",
+            "@end\n",
+            "@def jmp {n}\n",
+            "        if x1 != 0 goto An\n",
+            "@end\n",
+            "        y <- pick x1 x2\n",
+            "[A1]    jmp 1\n",
+        ));
+        let warnings: Vec<_> = program.warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].starts_with("Warning [line "), "{}", warnings[0]);
+        assert!(warnings[0].contains("Unused parameter o in macro"), "{}", warnings[0]);
+
+        let plain = parse("        y <- x2\n[A1]    if x1 != 0 goto A1");
+        assert_eq!(program.instructions, plain.instructions);
+        assert_eq!(program.labels, plain.labels);
+    }
+
+    #[test]
+    fn suppress_warning_silences_unused_parameters() {
+        let program = parse(concat!(
+            "@def {v} <- first of {a} {b}\n",
+            "@suppress-warning\n",
+            "        v <- a\n",
+            "@end\n",
+            "y <- first of x1 x2\n",
+        ));
+        assert!(program.warnings.is_empty());
+        assert_eq!(program.instructions, parse("y <- x1").instructions);
+    }
+
+    #[test]
+    fn suppress_warning_outside_macro_is_rejected() {
+        let err = parse_error("@suppress-warning\ny <- y + 1");
+        assert!(err.contains("Unexpected @suppress-warning directive"), "{}", err);
+    }
+
+    #[test]
+    fn empty_macro_expansion_is_rejected() {
+        let err = parse_error(concat!(
+            "@def skip {v}\n",
+            "        # nothing to do here\n",
+            "\n",
+            "@end\n",
+            "skip x1\n",
+        ));
+        assert!(err.contains("Macro skip {v} expanded to no instructions"), "{}", err);
+    }
+
+    #[test]
+    fn label_only_macro_expansion_is_accepted() {
+        let program = parse(concat!(
+            "@def mark {v}\n",
+            "@suppress-warning\n",
+            "[%A1]\n",
+            "@end\n",
+            "mark x1\n",
+            "y <- y + 1\n",
+        ));
+        assert_eq!(program.instructions, parse("y <- y + 1").instructions);
+        assert_eq!(program.labels.len(), 1);
+    }
+
+    #[test]
+    fn jump_into_macro_expansion_is_rejected() {
+        // The jump target is only built by substituting the argument, so the label pre-pass
+        // never sees it and it lands on a label generated for the expansion of y <- x1
+        for call in ["jmp 2", "jmp 3"] {
+            let source = format!(
+                "@def jmp {{n}}\n        if x1 != 0 goto An\n@end\ny <- x1\nz9 <- z9 + 1\n{}",
+                call,
+            );
+            let err = parse_error(&source);
+            assert!(err.contains("which is internal to a macro expansion"), "{}: {}", call, err);
+        }
+    }
+
+    /// Write module files to a fresh temporary directory, to use as the lib path
+    fn module_dir(test: &str, modules: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("slang-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in modules {
+            std::fs::write(dir.join(format!("{}.s", name)), source).unwrap();
+        }
+        dir
+    }
+
+    fn parse_with_modules(test: &str, modules: &[(&str, &str)], source: &str) -> Result<Program, String> {
+        let lib_path = [module_dir(test, modules)];
+        let program = Program::from_reader(source.as_bytes(), &lib_path);
+        std::fs::remove_dir_all(&lib_path[0]).unwrap();
+        program.map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn imported_macros_expand() {
+        let modules = [("double", "@module double\n@def {v} <- 2 * {a}\n        v <- a + a\n@end\n")];
+        let program = parse_with_modules("import", &modules, "@import double\ny <- 2 * x1").unwrap();
+        assert_eq!(program.imports, vec!["double".to_string()]);
+        assert_eq!(program.instructions, parse("y <- x1 + x1").instructions);
+    }
+
+    #[test]
+    fn circular_imports_are_rejected() {
+        let modules = [
+            ("a", "@module a\n@import b\n"),
+            ("b", "@module b\n@import a\n"),
+            ("c", "@module c\n@import c\n"),
+        ];
+        let err = parse_with_modules("cycle", &modules, "@import a\ny <- y + 1").err().unwrap();
+        assert!(err.contains("Circular import a -> b -> a"), "{}", err);
+
+        let err = parse_with_modules("self-import", &modules, "@import c\ny <- y + 1").err().unwrap();
+        assert!(err.contains("Circular import c -> c"), "{}", err);
+    }
+
+    #[test]
+    fn directive_must_be_followed_by_whitespace() {
+        let modules = [("c", "@module c\n@def {v} <- 2 * {a}\n        v <- a + a\n@end\n")];
+        for source in ["@importc\ny <- 2 * x1", "@modulec\ny <- y + 1"] {
+            let err = parse_with_modules("directive", &modules, source).err().unwrap();
+            assert!(err.contains("Unknown directive"), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn module_errors_name_the_module() {
+        let modules = [
+            ("broken", "@module broken\n\n@def {v} <- 2 * {a}\n        v <- a + a\n@bogus\n@end\n"),
+            ("outer", "@module outer\n@import broken\n"),
+            ("bad_var", "@module bad_var\nx99999999999999999999 <- x99999999999999999999 + 1\n"),
+        ];
+        let err = parse_with_modules("module-error", &modules, "@import broken\ny <- y + 1").err().unwrap();
+        assert!(err.starts_with("ParseError [module broken, line 5]: Unknown directive"), "{}", err);
+
+        let err = parse_with_modules("nested-module-error", &modules, "@import outer\ny <- y + 1").err().unwrap();
+        assert!(err.starts_with("ParseError [module broken, line 5]"), "{}", err);
+
+        let err = parse_with_modules("module-other-error", &modules, "@import bad_var\ny <- y + 1").err().unwrap();
+        assert!(err.contains("In module bad_var: "), "{}", err);
+    }
+
+    #[test]
+    fn diamond_imports_load_once() {
+        let modules = [
+            ("a", "@module a\n@import c\n"),
+            ("b", "@module b\n@import c\n"),
+            ("c", "@module c\n@def {v} <- first of {a} {b}\n        v <- a\n@end\n"),
+        ];
+        let program = parse_with_modules("diamond", &modules, "@import a\n@import b\ny <- first of x1 x2").unwrap();
+        assert_eq!(program.imports, vec!["c".to_string(), "a".to_string(), "b".to_string()]);
+        assert_eq!(program.macros.iter().filter(|m| m.source == "{v} <- first of {a} {b}").count(), 1);
+        assert_eq!(program.warnings.len(), 1);
+    }
+
+    #[test]
+    fn module_warnings_are_kept() {
+        let modules = [
+            ("first", "@module first\n@def {v} <- first of {a} {b}\n        v <- a\n@end\n"),
+            ("outer", "@module outer\n@import first\n"),
+        ];
+        let program = parse_with_modules("warnings", &modules, "@import outer\ny <- first of x1 x2").unwrap();
+        let warnings: Vec<_> = program.warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].starts_with("Warning [module first, line 2]"), "{}", warnings[0]);
+        assert!(warnings[0].contains("Unused parameter b"), "{}", warnings[0]);
+    }
+
+    /// Parses a generated 1000-line program mixing every instruction kind, with a macro call
+    /// every tenth line. Run with `cargo test --release -- --ignored --nocapture parse_benchmark`
+    #[test]
+    #[ignore]
+    fn parse_benchmark() {
+        let vars = ["y", "x1", "x2", "z1", "z2", "z3"];
+        let source: String = (0..1000).map(|i| {
+            let v = vars[i * 7 % vars.len()];
+            match i % 10 {
+                0..=3 => format!("        {v} <- {v} + 1\n"),
+                4..=6 => format!("        {v} <- {v} - 1\n"),
+                7 => format!("        if {v} != 0 goto E{i}\n"),
+                8 => "        nop\n".to_string(),
+                _ => format!("        z{i} <- x1 + x2\n"),
+            }
+        }).collect();
+
+        let runs = 5;
+        let start = std::time::Instant::now();
+        for _ in 0..runs {
+            assert!(parse(&source).instructions.len() > 1000);
+        }
+        println!("Parsed 1000 lines in {:?} on average", start.elapsed() / runs);
+    }
+
+    #[test]
+    fn single_increment_program_number() {
+        // #(x1 <- x1 + 1) = <0, <1, 1>> = 10, so the program number is 2^10 - 1
+        let program = parse("x1 <- x1 + 1");
+        assert_eq!(program.number(), Some(BigUint::from(1023)));
+        assert_eq!(program.to_string(), "1023");
+    }
+
+    #[test]
+    fn labelled_loop_program_number() {
+        // Example from Davis, Sigal & Weyuker: [A] X <- X + 1, IF X != 0 GOTO A
+        // has instruction numbers <1, <1, 1>> = 21 and <0, <3, 1>> = 46
+        let program = parse("[A1] x1 <- x1 + 1\n     if x1 != 0 goto A1");
+        assert_eq!(program.get_number(), vec![BigUint::from(21), BigUint::from(46)]);
+        assert_eq!(program.to_string(), "18586928403505481978329694207");
+    }
+
+    #[test]
+    fn id_program_round_trips_through_number() {
+        let program = parse(include_str!("../programs/id.s"));
+        let number = program.number().unwrap();
+        let decoded = Program::from_number(&number).unwrap();
+        assert_eq!(decoded.instructions, program.instructions);
+        assert_eq!(decoded.labels, program.labels);
+        assert_eq!(decoded.number(), Some(number));
+    }
+
+    #[test]
+    fn instruction_at_label_lookup() {
+        let program = parse("        x1 <- x1 - 1\n[B2]    y <- y + 1\n        if y != 0 goto A3");
+        assert_eq!(
+            program.instruction_at_label(&Label::new(1, 2)),
+            Some(&Instruction::Increment { var: Variable::Y }),
+        );
+        assert_eq!(program.instruction_at_label(&Label::new(0, 1)), None);
+        assert_eq!(program.label_at_instruction(1), Some(Label::new(1, 2)));
+        assert_eq!(program.label_at_instruction(0), None);
+    }
+
+    #[test]
+    fn label_at_instruction_prefers_lowest_label() {
+        let source = "@def mark\n[%A1]\n@end\n        mark\n[B1]    y <- y + 1";
+        for _ in 0..10 {
+            let program = parse(source);
+            assert_eq!(program.labels.len(), 2);
+            // B1 is label number 2, and the A2 generated by the expansion is label number 6
+            assert_eq!(program.label_at_instruction(0), Some(Label::new(1, 1)));
+            assert_eq!(program.instruction_at_label(&Label::new(0, 2)), program.instructions.first());
+            assert_eq!(program.instruction_at_label(&Label::new(1, 1)), program.instructions.first());
+        }
+    }
+
+    #[test]
+    fn shared_label_program_number_uses_lowest_label() {
+        let source = "@def mark\n[%A1]\n@end\n        mark\n[A1]    y <- y + 1";
+        let expected = parse("[A1]    y <- y + 1").number();
+        for _ in 0..10 {
+            let program = parse(source);
+            assert_eq!(program.labels.len(), 2);
+            assert_eq!(program.number(), expected);
+        }
+    }
+
+    #[test]
+    fn unsupported_instruction_number_is_rejected() {
+        // 15 + 1 = 2^4, and 4 = <0, <0, 1>> encodes x1 <- x1
+        let err = Program::from_number(&BigUint::from(15)).err().unwrap().to_string();
+        assert!(err.contains("Instruction x1 <- x1 is not supported"), "{}", err);
+
+        // 1 + 1 = 2^1, and 1 = <1, <0, 0>> encodes [A1] nop, which is y <- y
+        let decoded = Program::from_number(&BigUint::from(1)).unwrap();
+        assert_eq!(decoded.instructions, vec![Instruction::Nop]);
+        assert_eq!(decoded.number(), Some(BigUint::from(1)));
+    }
+
+    #[test]
+    fn large_decoded_program_matches_its_number() {
+        // 40002 = <0, <1, 5000>> encodes z2500 <- z2500 + 1, so the number has 40002 bits
+        let number = BigUint::one().shl(40002).sub_one();
+        let decoded = Program::from_number(&number).unwrap();
+        assert_eq!(decoded.instructions, parse("z2500 <- z2500 + 1").instructions);
+        assert!(decoded.has_number(&number));
+        assert_eq!(decoded.number(), Some(number.clone()));
+        assert!(!decoded.has_number(&number.sub_one()));
+    }
+
+    #[test]
+    fn meta_instructions_are_not_numbered() {
+        let program = parse("state\nx1 <- x1 + 1\nprint y");
+        assert_eq!(program.to_string(), "1023");
+    }
+
+    #[test]
+    fn large_program_number_prints_exponents() {
+        let program = parse("[E9] z9 <- z9 + 1\nif z9 != 0 goto E9");
+        assert_eq!(program.number(), None);
+        assert!(program.to_string().starts_with('['));
+        assert!(program.to_string().ends_with("] - 1"));
+    }
 }